@@ -1,9 +1,12 @@
 use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use http_body_util::combinators::BoxBody;
 use http_body_util::{BodyExt, Full};
-use hyper::body::Bytes;
-use hyper::{Response, StatusCode};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Request, Response, StatusCode};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::path::PathBuf;
 
@@ -13,26 +16,90 @@ pub enum FilterMode {
     Whitelist,
 }
 
+/// Intermediate representation matching the TOML layout, converted into a
+/// [`FilterRules`] so domains can be split into exact and wildcard sets.
 #[derive(Deserialize)]
+struct FilterRulesConfig {
+    mode: FilterMode,
+    domains: Vec<String>,
+}
+
+impl TryFrom<FilterRulesConfig> for FilterRules {
+    type Error = std::convert::Infallible;
+
+    fn try_from(config: FilterRulesConfig) -> std::result::Result<Self, Self::Error> {
+        Ok(FilterRules::from_domains(config.mode, config.domains))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "FilterRulesConfig")]
 pub struct FilterRules {
     mode: FilterMode,
-    domains: HashSet<String>,
+    /// Domains matched by exact equality.
+    exact: HashSet<String>,
+    /// Suffix rules (from `*.example.com` entries) that match the suffix
+    /// itself and all of its subdomains. A bare entry is always exact.
+    wildcards: HashSet<String>,
 }
 
 impl FilterRules {
     #[allow(dead_code)]
     pub fn new_blacklist<S: Into<String>>(domains: Vec<S>) -> Self {
-        FilterRules {
-            mode: FilterMode::Blacklist,
-            domains: domains.into_iter().map(|s| s.into()).collect(),
-        }
+        FilterRules::from_domains(
+            FilterMode::Blacklist,
+            domains.into_iter().map(|s| s.into()),
+        )
     }
 
     #[allow(dead_code)]
     pub fn new_whitelist<S: Into<String>>(domains: Vec<S>) -> Self {
+        FilterRules::from_domains(
+            FilterMode::Whitelist,
+            domains.into_iter().map(|s| s.into()),
+        )
+    }
+
+    /// Split a flat list of domain entries into exact and wildcard sets.
+    /// An entry prefixed with `*.` (e.g. `*.example.com`) is stored as a
+    /// wildcard suffix that matches the apex domain and every subdomain.
+    fn from_domains<I: IntoIterator<Item = String>>(mode: FilterMode, domains: I) -> Self {
+        let mut exact = HashSet::new();
+        let mut wildcards = HashSet::new();
+        for entry in domains {
+            match entry.strip_prefix("*.") {
+                Some(suffix) => {
+                    wildcards.insert(suffix.to_string());
+                }
+                None => {
+                    exact.insert(entry);
+                }
+            }
+        }
         FilterRules {
-            mode: FilterMode::Whitelist,
-            domains: domains.into_iter().map(|s| s.into()).collect(),
+            mode,
+            exact,
+            wildcards,
+        }
+    }
+
+    /// Return `true` if the domain matches any exact or wildcard rule.
+    /// Exact membership is checked first; otherwise the domain's parent
+    /// suffixes are walked right-to-left against the wildcard set.
+    fn matches(&self, domain: &str) -> bool {
+        if self.exact.contains(domain) {
+            return true;
+        }
+        // Test the domain itself and each parent suffix (a.b.c -> a.b.c, b.c, c).
+        let mut suffix = domain;
+        loop {
+            if self.wildcards.contains(suffix) {
+                return true;
+            }
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => return false,
+            }
         }
     }
 
@@ -40,11 +107,11 @@ impl FilterRules {
     /// This function extracts the domain from the host (ignoring port)
     /// and checks it against the filter mode and domain list.
     pub fn is_allowed(&self, host: &str) -> bool {
-        let domain = host.split(':').next().unwrap_or(host).to_string();
+        let domain = host.split(':').next().unwrap_or(host);
 
         match self.mode {
-            FilterMode::Blacklist => !self.domains.contains(&domain),
-            FilterMode::Whitelist => self.domains.contains(&domain),
+            FilterMode::Blacklist => !self.matches(domain),
+            FilterMode::Whitelist => self.matches(domain),
         }
     }
 
@@ -72,6 +139,152 @@ impl FilterRules {
     }
 }
 
+/// Proxy authentication rules, parallel to [`FilterRules`].
+///
+/// Credentials are stored as hex-encoded SHA-256 digests of the raw
+/// `username:password` pair so the plaintext never lives in the config or in
+/// memory. When present, the proxy requires a matching `Proxy-Authorization`
+/// header before forwarding any request.
+#[derive(Deserialize)]
+pub struct ProxyAuth {
+    #[serde(default = "default_realm")]
+    realm: String,
+    credentials: HashSet<String>,
+}
+
+fn default_realm() -> String {
+    "proxy".to_string()
+}
+
+impl ProxyAuth {
+    #[allow(dead_code)]
+    pub fn new<S: Into<String>>(realm: S, credentials: Vec<String>) -> Self {
+        ProxyAuth {
+            realm: realm.into(),
+            credentials: credentials.into_iter().collect(),
+        }
+    }
+
+    /// The realm advertised in the `Proxy-Authenticate` challenge.
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// Check whether a request carries valid Basic proxy credentials.
+    ///
+    /// The `Proxy-Authorization` header is parsed, the `Basic` token is
+    /// base64-decoded, hashed, and compared against the accepted digests in
+    /// constant time. Any malformed or missing header is treated as a failure.
+    pub fn check(&self, req: &Request<Incoming>) -> bool {
+        let header = match req
+            .headers()
+            .get("proxy-authorization")
+            .and_then(|h| h.to_str().ok())
+        {
+            Some(h) => h,
+            None => return false,
+        };
+
+        let token = match header
+            .split_once(' ')
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Basic"))
+            .map(|(_, token)| token.trim())
+        {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let decoded = match STANDARD.decode(token) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+
+        let digest = hex_encode(&Sha256::digest(&decoded));
+
+        self.credentials
+            .iter()
+            .any(|accepted| constant_time_eq(accepted.as_bytes(), digest.as_bytes()))
+    }
+
+    /// Read proxy authentication rules from a TOML configuration file.
+    #[allow(dead_code)]
+    pub fn read_config_file<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        if !path.exists() {
+            return Err(anyhow::anyhow!(
+                "Auth config file does not exist: {}",
+                path.display()
+            ));
+        }
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            anyhow::anyhow!("Failed to read auth config file {}: {}", path.display(), e)
+        })?;
+        let config: Self = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse auth config file: {}", e))?;
+        Ok(config)
+    }
+}
+
+/// Hex-encode a byte slice as a lowercase string.
+fn hex_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+/// Compare two byte slices without short-circuiting on the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+/// Generate a `407 Proxy Authentication Required` response carrying the
+/// `Proxy-Authenticate` challenge for the given realm.
+pub fn auth_required_response(realm: &str) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let response = Response::builder()
+        .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+        .header(
+            "Proxy-Authenticate",
+            format!("Basic realm=\"{}\"", realm),
+        )
+        .header("Content-Type", "text/plain")
+        .body(
+            Full::new(Bytes::from("Proxy authentication required"))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to build auth response: {}", e))?;
+
+    Ok(response)
+}
+
+/// Generate a `421 Misdirected Request` response.
+/// This is returned when a plain-HTTP request's URI authority disagrees with
+/// its `Host` header, a common domain-fronting technique.
+pub fn misdirected_response(host: &str) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    let body = format!("Request for {} was misdirected (host mismatch)", host);
+
+    let response = Response::builder()
+        .status(StatusCode::MISDIRECTED_REQUEST)
+        .header("Content-Type", "text/plain")
+        .body(
+            Full::new(Bytes::from(body))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to build misdirected response: {}", e))?;
+
+    Ok(response)
+}
+
 /// Generate a blocked response for a given host.
 /// This function creates an HTTP 403 Forbidden response
 /// with a message indicating that access to the specified host
@@ -98,11 +311,46 @@ mod tests {
 
     #[test]
     fn test_read_file() -> anyhow::Result<()> {
-        let rules = FilterRules::read_config_file(
-            "/Users/guangyu/RustroverProjects/hyper-proxy-lite/filter_rules_example.toml",
-        )?;
+        let rules = FilterRules::read_config_file(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/filter_rules_example.toml"
+        ))?;
         assert_eq!(rules.mode, crate::addon::filter::FilterMode::Blacklist);
-        assert!(rules.domains.contains("example.com"));
+        assert!(rules.exact.contains("example.com"));
         Ok(())
     }
+
+    #[test]
+    fn test_wildcard_matches_subdomains() {
+        let rules = FilterRules::new_blacklist(vec!["*.example.com"]);
+        // Subdomains at any depth are blocked.
+        assert!(!rules.is_allowed("a.b.example.com"));
+        assert!(!rules.is_allowed("ads.example.com"));
+        // The apex domain itself is also covered by the wildcard.
+        assert!(!rules.is_allowed("example.com"));
+        // Unrelated domains and look-alikes are untouched.
+        assert!(rules.is_allowed("notexample.com"));
+        assert!(rules.is_allowed("example.com.evil.com"));
+    }
+
+    #[test]
+    fn test_exact_does_not_match_subdomains() {
+        let rules = FilterRules::new_blacklist(vec!["example.com"]);
+        assert!(!rules.is_allowed("example.com"));
+        // A bare exact entry must not block subdomains.
+        assert!(rules.is_allowed("ads.example.com"));
+    }
+
+    #[test]
+    fn test_overlapping_exact_and_wildcard() {
+        // `deep.inner.test` is listed both exactly and through `*.inner.test`.
+        let rules =
+            FilterRules::new_whitelist(vec!["apex.test", "*.inner.test", "deep.inner.test"]);
+        assert!(rules.is_allowed("apex.test"));
+        assert!(rules.is_allowed("inner.test"));
+        assert!(rules.is_allowed("deep.inner.test"));
+        assert!(!rules.is_allowed("other.test"));
+        // Exact entry does not leak to its subdomains.
+        assert!(!rules.is_allowed("sub.apex.test"));
+    }
 }
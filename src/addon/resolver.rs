@@ -0,0 +1,160 @@
+use anyhow::Result;
+use hickory_resolver::TokioAsyncResolver;
+use hyper_util::client::legacy::connect::dns::Name;
+use lru::LruCache;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use std::vec::IntoIter;
+use tokio::sync::Mutex;
+
+/// Default number of distinct hosts kept in the resolution cache.
+const DEFAULT_CAPACITY: usize = 256;
+/// Fallback time-to-live applied when the resolver does not report one.
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// A cached DNS answer together with the instant it stops being valid.
+struct CacheEntry {
+    addrs: Vec<SocketAddr>,
+    expires_at: Instant,
+}
+
+/// An async DNS resolver with an LRU cache in front of it.
+///
+/// Repeated requests to the same host reuse the cached addresses instead of
+/// re-querying the OS resolver, which keeps the proxy responsive when traffic
+/// is concentrated on a small set of hosts. Entries honour the record TTL and
+/// are re-resolved once they expire.
+pub struct CacheResolver {
+    cache: Arc<Mutex<LruCache<String, CacheEntry>>>,
+    resolver: TokioAsyncResolver,
+    ttl: Duration,
+}
+
+impl CacheResolver {
+    /// Build a resolver from the system configuration with the default cache
+    /// capacity.
+    pub fn new() -> Result<Self> {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Build a resolver whose cache holds at most `capacity` hosts. A zero
+    /// capacity falls back to the default capacity.
+    pub fn with_capacity(capacity: usize) -> Result<Self> {
+        let capacity =
+            NonZeroUsize::new(capacity).unwrap_or_else(|| NonZeroUsize::new(DEFAULT_CAPACITY).unwrap());
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| anyhow::anyhow!("Failed to create DNS resolver: {}", e))?;
+        Ok(CacheResolver {
+            cache: Arc::new(Mutex::new(LruCache::new(capacity))),
+            resolver,
+            ttl: DEFAULT_TTL,
+        })
+    }
+
+    /// Resolve `host` to a list of socket addresses, consulting the cache first.
+    ///
+    /// `host` may be a bare hostname or a `host:port` authority; a missing port
+    /// defaults to 443 so CONNECT authorities that omit it still resolve. On a
+    /// cache miss the name is resolved, every returned A/AAAA record is kept in
+    /// order, and the answer is stored until its TTL elapses. Expired entries
+    /// are dropped on the way through so stale IPs eventually disappear.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<SocketAddr>> {
+        let (name, port) = split_host_port(host);
+
+        {
+            let mut cache = self.cache.lock().await;
+            if let Some(entry) = cache.get(host) {
+                if entry.expires_at > Instant::now() {
+                    return Ok(entry.addrs.clone());
+                }
+                // Stale answer: drop it and fall through to a fresh lookup.
+                cache.pop(host);
+            }
+        }
+
+        let lookup = self
+            .resolver
+            .lookup_ip(name)
+            .await
+            .map_err(|e| anyhow::anyhow!("DNS resolution failed for {}: {}", name, e))?;
+
+        let ttl = lookup
+            .valid_until()
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+            .unwrap_or(self.ttl);
+
+        let addrs: Vec<SocketAddr> = lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect();
+        if addrs.is_empty() {
+            return Err(anyhow::anyhow!("No addresses resolved for {}", name));
+        }
+
+        let mut cache = self.cache.lock().await;
+        cache.put(
+            host.to_string(),
+            CacheEntry {
+                addrs: addrs.clone(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+
+        Ok(addrs)
+    }
+}
+
+/// Adapter that lets [`CacheResolver`] back a hyper `HttpConnector`.
+///
+/// The connector hands us a bare [`Name`] and fills in the port itself on
+/// returned addresses whose port is 0, so the placeholder port from
+/// [`CacheResolver`] is reset to 0 here.
+#[derive(Clone)]
+pub struct CacheDns(pub Arc<CacheResolver>);
+
+impl tower_service::Service<Name> for CacheDns {
+    type Response = IntoIter<SocketAddr>;
+    type Error = anyhow::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<IntoIter<SocketAddr>>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let addrs = resolver.resolve(name.as_str()).await?;
+            let addrs: Vec<SocketAddr> = addrs
+                .into_iter()
+                .map(|mut addr| {
+                    addr.set_port(0);
+                    addr
+                })
+                .collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+/// Split an authority into its host and port, defaulting to 443 when the port
+/// is absent or unparsable. IPv6 literals wrapped in brackets are handled.
+fn split_host_port(host: &str) -> (&str, u16) {
+    if let Some(rest) = host.strip_prefix('[') {
+        // [::1]:443 style IPv6 literal with a port.
+        if let Some((addr, port)) = rest.split_once("]:") {
+            return (addr, port.parse().unwrap_or(443));
+        }
+        if let Some(addr) = rest.strip_suffix(']') {
+            return (addr, 443);
+        }
+    }
+
+    match host.rsplit_once(':') {
+        Some((name, port)) => (name, port.parse().unwrap_or(443)),
+        None => (host, 443),
+    }
+}
@@ -1,6 +1,9 @@
 mod addon;
 
-use crate::addon::filter::{blocked_response, FilterRules};
+use crate::addon::filter::{
+    auth_required_response, blocked_response, misdirected_response, FilterRules, ProxyAuth,
+};
+use crate::addon::resolver::{CacheDns, CacheResolver};
 use anyhow::Result;
 use http_body_util::{combinators::BoxBody, BodyExt, Empty};
 use hyper::body::Bytes;
@@ -10,7 +13,8 @@ use hyper::upgrade::Upgraded;
 use hyper::{body::Incoming, Method, Request, Response};
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioIo;
-use tokio::io::copy_bidirectional;
+use serde::Deserialize;
+use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
 #[tokio::main]
@@ -18,14 +22,41 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind("127.0.0.1:8080").await?;
     println!("Server starts at http://127.0.0.1:8080");
 
-    let rules = FilterRules::new_blacklist(vec!["goldentech.digital"]);
+    // Use the `*.` suffix form so the apex domain and every subdomain (e.g.
+    // `ads.goldentech.digital`) are blocked; a bare entry matches only the
+    // exact domain.
+    let rules = FilterRules::new_blacklist(vec!["*.goldentech.digital"]);
     let rules = std::sync::Arc::new(rules);
 
+    let resolver = std::sync::Arc::new(CacheResolver::new()?);
+
+    // Proxy authentication is off unless a `ProxyAuth` config is loaded.
+    let auth: Option<std::sync::Arc<ProxyAuth>> = None;
+
+    // When enabled, reject requests whose tunnel/URI authority disagrees with
+    // the application-layer host (Host header or TLS SNI).
+    let detect_fronting = false;
+
+    // PROXY protocol emission towards upstream is off by default.
+    let proxy_mode = ProxyMode::Off;
+
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer_addr) = listener.accept().await?;
         let rules = rules.clone();
+        let resolver = resolver.clone();
+        let auth = auth.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_client(stream, rules).await {
+            if let Err(e) = handle_client(
+                stream,
+                peer_addr,
+                rules,
+                resolver,
+                auth,
+                detect_fronting,
+                proxy_mode,
+            )
+            .await
+            {
                 eprintln!("Error: {}", e);
             }
         });
@@ -37,9 +68,19 @@ async fn main() -> Result<()> {
 /// and CONNECT requests for HTTPS tunneling.
 /// It uses the `hyper` crate to manage the HTTP protocol and upgrades connections as needed.
 /// It takes a `TcpStream` representing the client connection and a reference
-/// to the filter rules for domain filtering.
+/// to the filter rules for domain filtering along with the shared DNS resolver.
+/// The client's `SocketAddr` is threaded through so `handle_http` can add
+/// forwarding headers identifying the original client.
 /// It returns a Result indicating success or failure of the handling process.
-async fn handle_client(stream: TcpStream, rules: std::sync::Arc<FilterRules>) -> Result<()> {
+async fn handle_client(
+    stream: TcpStream,
+    peer_addr: std::net::SocketAddr,
+    rules: std::sync::Arc<FilterRules>,
+    resolver: std::sync::Arc<CacheResolver>,
+    auth: Option<std::sync::Arc<ProxyAuth>>,
+    detect_fronting: bool,
+    proxy_mode: ProxyMode,
+) -> Result<()> {
     let io = TokioIo::new(stream);
 
     http1::Builder::new()
@@ -49,7 +90,9 @@ async fn handle_client(stream: TcpStream, rules: std::sync::Arc<FilterRules>) ->
             io,
             service_fn(move |req| {
                 let rules = rules.clone();
-                proxy(req, rules)
+                let resolver = resolver.clone();
+                let auth = auth.clone();
+                proxy(req, peer_addr, rules, resolver, auth, detect_fronting, proxy_mode)
             }),
         )
         .with_upgrades()
@@ -65,9 +108,26 @@ async fn handle_client(stream: TcpStream, rules: std::sync::Arc<FilterRules>) ->
 /// It also checks the filter rules to determine if the request should be blocked.
 /// If the request is blocked, it returns a blocked response.
 async fn proxy(
-    req: Request<Incoming>,
+    mut req: Request<Incoming>,
+    peer_addr: std::net::SocketAddr,
     rules: std::sync::Arc<FilterRules>,
+    resolver: std::sync::Arc<CacheResolver>,
+    auth: Option<std::sync::Arc<ProxyAuth>>,
+    detect_fronting: bool,
+    proxy_mode: ProxyMode,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+    // When proxy authentication is enabled, reject requests that do not carry
+    // valid credentials before doing any filtering or forwarding. The
+    // `Proxy-Authorization` header is stripped afterwards so credentials never
+    // reach the upstream server.
+    if let Some(auth) = auth.as_deref() {
+        if !auth.check(&req) {
+            println!("🔒 AUTH REQUIRED");
+            return auth_required_response(auth.realm());
+        }
+        req.headers_mut().remove("proxy-authorization");
+    }
+
     let host = req
         .uri()
         .authority()
@@ -86,9 +146,9 @@ async fn proxy(
     }
 
     if req.method() == Method::CONNECT {
-        handle_connect(req).await
+        handle_connect(req, peer_addr, resolver, rules, detect_fronting, proxy_mode).await
     } else {
-        handle_http(req).await
+        handle_http(req, peer_addr, resolver, detect_fronting).await
     }
 }
 
@@ -99,10 +159,52 @@ async fn proxy(
 /// It uses a hyper client to send the request and retrieve the response.
 /// The response body is boxed for compatibility with the expected return type.
 /// It also includes error handling to manage potential issues during the request process.
-async fn handle_http(mut req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+async fn handle_http(
+    mut req: Request<Incoming>,
+    peer_addr: std::net::SocketAddr,
+    resolver: std::sync::Arc<CacheResolver>,
+    detect_fronting: bool,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
     println!("HTTP: {} {}", req.method(), req.uri());
 
-    let client = Client::builder(hyper_util::rt::TokioExecutor::new()).build_http();
+    // When domain-fronting detection is enabled, the request-line authority
+    // must agree with the `Host` header; a mismatch is misdirected.
+    if detect_fronting {
+        let uri_host = req.uri().host().map(|h| h.to_ascii_lowercase());
+        let header_host = req
+            .headers()
+            .get("host")
+            .and_then(|h| h.to_str().ok())
+            .map(|h| host_without_port(h).to_ascii_lowercase());
+        if let (Some(uri_host), Some(header_host)) = (uri_host, header_host) {
+            if uri_host != header_host {
+                println!("🚨 DOMAIN FRONTING: {} != {}", uri_host, header_host);
+                return misdirected_response(&uri_host);
+            }
+        }
+    }
+
+    // WebSocket / upgrade handshakes must keep their `Connection`/`Upgrade`
+    // headers intact, so skip hop-by-hop stripping on that path. Normal
+    // requests have connection-scoped headers removed so they never leak to the
+    // upstream server.
+    let upgrade = is_upgrade_request(req.headers());
+    if !upgrade {
+        strip_hop_by_hop_headers(req.headers_mut());
+    }
+    append_forwarded_for(req.headers_mut(), peer_addr.ip());
+
+    // NOTE: PROXY protocol emission (see `ProxyMode`) is scoped to the
+    // CONNECT/`tunnel` path. Plain-HTTP forwarding and upgrade splices go
+    // through hyper's pooled legacy `Client`, which owns connection setup and
+    // reuse; there is no single "connection start" hook here at which to inject
+    // a once-per-connection PROXY header, so origin servers reached over plain
+    // HTTP do not receive one.
+    let mut connector = hyper_util::client::legacy::connect::HttpConnector::new_with_resolver(
+        CacheDns(resolver.clone()),
+    );
+    connector.enforce_http(false);
+    let client = Client::builder(hyper_util::rt::TokioExecutor::new()).build(connector);
     let uri = req.uri().clone();
 
     let uri_string = format!(
@@ -116,21 +218,132 @@ async fn handle_http(mut req: Request<Incoming>) -> Result<Response<BoxBody<Byte
         .parse()
         .map_err(|_| anyhow::anyhow!("Failed to parse URI: {}", uri_string))?;
 
-    let response = client
+    if upgrade {
+        // Capture the client-side upgrade future before forwarding, then send
+        // the request upstream. If the upstream agrees to switch protocols we
+        // splice the two upgraded connections together, mirroring `tunnel`.
+        let client_upgrade = hyper::upgrade::on(&mut req);
+        let mut response = client
+            .request(req)
+            .await
+            .map_err(|e| anyhow::anyhow!("HTTP request error: {}", e))?;
+
+        if response.status() == hyper::StatusCode::SWITCHING_PROTOCOLS {
+            let upstream_upgrade = hyper::upgrade::on(&mut response);
+            tokio::spawn(async move {
+                match tokio::try_join!(client_upgrade, upstream_upgrade) {
+                    Ok((client_io, upstream_io)) => {
+                        let mut client_io = TokioIo::new(client_io);
+                        let mut upstream_io = TokioIo::new(upstream_io);
+                        if let Err(e) =
+                            copy_bidirectional(&mut client_io, &mut upstream_io).await
+                        {
+                            eprintln!("Upgrade relay error: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Upgrade error: {}", e),
+                }
+            });
+        }
+
+        // Return the upstream response (the 101, or whatever it replied)
+        // unmodified so the handshake completes.
+        return Ok(response.map(|body| body.boxed()));
+    }
+
+    let mut response = client
         .request(req)
         .await
         .map_err(|e| anyhow::anyhow!("HTTP request error: {}", e))?;
 
+    // Apply the same hop-by-hop hygiene to the response before handing it back
+    // to the client.
+    strip_hop_by_hop_headers(response.headers_mut());
+
     Ok(response.map(|body| body.boxed()))
 }
 
+/// Return `true` if the request is an HTTP upgrade (e.g. a WebSocket
+/// handshake): it carries an `Upgrade` header and a `Connection` header whose
+/// token list includes `upgrade`.
+fn is_upgrade_request(headers: &hyper::HeaderMap) -> bool {
+    if !headers.contains_key(hyper::header::UPGRADE) {
+        return false;
+    }
+    headers
+        .get(hyper::header::CONNECTION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        })
+        .unwrap_or(false)
+}
+
+/// Hop-by-hop headers defined by RFC 7230 §6.1 that a proxy must not forward.
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailers",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Remove hop-by-hop headers from a header map.
+/// Besides the well-known set, any header named in the `Connection` header's
+/// comma-separated token list is also connection-scoped and is dropped.
+fn strip_hop_by_hop_headers(headers: &mut hyper::HeaderMap) {
+    // Collect the extra connection tokens before removing `Connection` itself.
+    let extra: Vec<String> = headers
+        .get("connection")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .map(|token| token.trim().to_ascii_lowercase())
+                .filter(|token| !token.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(name);
+    }
+    for name in extra {
+        headers.remove(name.as_str());
+    }
+}
+
+/// Append the client IP to the `X-Forwarded-For` header (creating it when
+/// absent) and record the forwarded protocol in `X-Forwarded-Proto`.
+fn append_forwarded_for(headers: &mut hyper::HeaderMap, client_ip: std::net::IpAddr) {
+    let client_ip = client_ip.to_string();
+    let value = match headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+        Some(existing) => format!("{}, {}", existing, client_ip),
+        None => client_ip,
+    };
+    if let Ok(value) = value.parse() {
+        headers.insert("x-forwarded-for", value);
+    }
+    headers.insert("x-forwarded-proto", hyper::header::HeaderValue::from_static("http"));
+}
+
 /// Handle CONNECT requests to establish a tunnel for HTTPS traffic.
 /// This function upgrades the connection and spawns a new task to manage
 /// the bidirectional data transfer between the client and the target server.
 /// It returns a 200 OK response to the client to indicate that the tunnel
 /// has been successfully established.
 /// It includes error handling to manage potential issues during the upgrade process.
-async fn handle_connect(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
+async fn handle_connect(
+    req: Request<Incoming>,
+    peer_addr: std::net::SocketAddr,
+    resolver: std::sync::Arc<CacheResolver>,
+    rules: std::sync::Arc<FilterRules>,
+    detect_fronting: bool,
+    proxy_mode: ProxyMode,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
     let addr = req
         .uri()
         .authority()
@@ -142,7 +355,17 @@ async fn handle_connect(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes
     tokio::spawn(async move {
         match hyper::upgrade::on(req).await {
             Ok(upgraded) => {
-                if let Err(e) = tunnel(upgraded, addr).await {
+                if let Err(e) = tunnel(
+                    upgraded,
+                    addr,
+                    peer_addr,
+                    resolver,
+                    rules,
+                    detect_fronting,
+                    proxy_mode,
+                )
+                .await
+                {
                     eprintln!("Tunnel error: {}", e);
                 }
             }
@@ -171,11 +394,471 @@ async fn handle_connect(req: Request<Incoming>) -> Result<Response<BoxBody<Bytes
 /// It returns a Result indicating success or failure of the tunneling operation.
 /// The function is asynchronous and leverages Tokio's async I/O capabilities.
 /// It is designed to work with upgraded HTTP connections, typically used for HTTPS tunneling.
-async fn tunnel(upgraded: Upgraded, addr: String) -> std::io::Result<()> {
-    let mut server = TcpStream::connect(addr).await?;
+async fn tunnel(
+    upgraded: Upgraded,
+    addr: String,
+    peer_addr: std::net::SocketAddr,
+    resolver: std::sync::Arc<CacheResolver>,
+    rules: std::sync::Arc<FilterRules>,
+    detect_fronting: bool,
+    proxy_mode: ProxyMode,
+) -> std::io::Result<()> {
     let mut upgraded = TokioIo::new(upgraded);
 
+    // When domain-fronting detection is enabled, peek at the TLS ClientHello to
+    // recover the real SNI server name. The bytes we read are replayed to the
+    // upstream server so the handshake still completes normally, and a failed
+    // or truncated read simply falls through to transparent tunnelling.
+    let mut head = Vec::new();
+    if detect_fronting {
+        let mut buf = [0u8; 4096];
+        if let Ok(n) = upgraded.read(&mut buf).await {
+            if n == 0 {
+                return Ok(());
+            }
+            head.extend_from_slice(&buf[..n]);
+            if let Some(sni) = parse_sni(&head) {
+                let authority_host = host_without_port(&addr);
+                if !sni.eq_ignore_ascii_case(authority_host) {
+                    eprintln!("🚨 DOMAIN FRONTING: SNI {} != CONNECT {}", sni, authority_host);
+                    return Ok(());
+                }
+                // Apply filter rules to the real SNI host, closing the hole
+                // where a blocked domain is reached via an allowed CONNECT
+                // target.
+                if !rules.is_allowed(&sni) {
+                    eprintln!("❌ BLOCKED (SNI): {}", sni);
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    let addrs = resolver
+        .resolve(&addr)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+
+    // Try the resolved addresses in order and tunnel through the first that
+    // accepts the connection.
+    let mut server = None;
+    let mut dst_addr = None;
+    let mut last_err = None;
+    for socket_addr in addrs {
+        match TcpStream::connect(socket_addr).await {
+            Ok(stream) => {
+                server = Some(stream);
+                dst_addr = Some(socket_addr);
+                break;
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    let mut server = server.ok_or_else(|| {
+        last_err.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Other, format!("No address for {}", addr))
+        })
+    })?;
+    let dst_addr = dst_addr.expect("connected server has a destination address");
+
+    // Announce the original client to the upstream via the PROXY protocol. The
+    // header must be written exactly once, before any other bytes cross the
+    // connection.
+    if let Some(header) = proxy_mode.header(peer_addr, dst_addr) {
+        server.write_all(&header).await?;
+    }
+
+    // Replay any bytes we consumed while peeking at the ClientHello, then relay
+    // the rest of the connection transparently.
+    if !head.is_empty() {
+        server.write_all(&head).await?;
+    }
+
     copy_bidirectional(&mut upgraded, &mut server).await?;
 
     Ok(())
 }
+
+/// PROXY protocol emission mode for upstream connections.
+/// When not [`ProxyMode::Off`], a header is written before any relayed traffic
+/// so the origin server learns the original client address.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+enum ProxyMode {
+    #[default]
+    Off,
+    V1,
+    V2,
+}
+
+impl ProxyMode {
+    /// Build the PROXY protocol header for a `src -> dst` TCP connection, or
+    /// `None` when emission is disabled or the address families disagree.
+    fn header(self, src: std::net::SocketAddr, dst: std::net::SocketAddr) -> Option<Vec<u8>> {
+        use std::net::SocketAddr;
+        match self {
+            ProxyMode::Off => None,
+            ProxyMode::V1 => {
+                let line = match (src, dst) {
+                    (SocketAddr::V4(s), SocketAddr::V4(d)) => format!(
+                        "PROXY TCP4 {} {} {} {}\r\n",
+                        s.ip(),
+                        d.ip(),
+                        s.port(),
+                        d.port()
+                    ),
+                    (SocketAddr::V6(s), SocketAddr::V6(d)) => format!(
+                        "PROXY TCP6 {} {} {} {}\r\n",
+                        s.ip(),
+                        d.ip(),
+                        s.port(),
+                        d.port()
+                    ),
+                    _ => return None,
+                };
+                Some(line.into_bytes())
+            }
+            ProxyMode::V2 => {
+                const SIGNATURE: [u8; 12] = [
+                    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+                ];
+                let mut out = Vec::with_capacity(28);
+                out.extend_from_slice(&SIGNATURE);
+                // Version 2 (high nibble) + PROXY command (low nibble).
+                out.push(0x21);
+                match (src, dst) {
+                    (SocketAddr::V4(s), SocketAddr::V4(d)) => {
+                        // AF_INET + STREAM.
+                        out.push(0x11);
+                        out.extend_from_slice(&12u16.to_be_bytes());
+                        out.extend_from_slice(&s.ip().octets());
+                        out.extend_from_slice(&d.ip().octets());
+                        out.extend_from_slice(&s.port().to_be_bytes());
+                        out.extend_from_slice(&d.port().to_be_bytes());
+                    }
+                    (SocketAddr::V6(s), SocketAddr::V6(d)) => {
+                        // AF_INET6 + STREAM.
+                        out.push(0x21);
+                        out.extend_from_slice(&36u16.to_be_bytes());
+                        out.extend_from_slice(&s.ip().octets());
+                        out.extend_from_slice(&d.ip().octets());
+                        out.extend_from_slice(&s.port().to_be_bytes());
+                        out.extend_from_slice(&d.port().to_be_bytes());
+                    }
+                    _ => return None,
+                }
+                Some(out)
+            }
+        }
+    }
+}
+
+/// Strip an optional `:port` suffix from a host/authority string.
+fn host_without_port(host: &str) -> &str {
+    match host.rfind(':') {
+        // A bracketed IPv6 literal without a port (e.g. `[::1]`) is kept
+        // intact; an unbracketed IPv6 literal is not recognised.
+        Some(idx) if !host[idx + 1..].contains(']') => &host[..idx],
+        _ => host,
+    }
+}
+
+/// Extract the SNI `server_name` from a buffer that begins with a TLS
+/// ClientHello record. Returns `None` on any malformed or truncated input so a
+/// partial read falls through to normal tunnelling rather than failing.
+fn parse_sni(buf: &[u8]) -> Option<String> {
+    // TLS record header: content type (0x16 handshake), version (2), length (2).
+    if buf.len() < 5 || buf[0] != 0x16 {
+        return None;
+    }
+    let record_len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    let body = buf.get(5..5 + record_len)?;
+
+    // Handshake header: type (0x01 ClientHello), length (3).
+    if body.len() < 4 || body[0] != 0x01 {
+        return None;
+    }
+    let mut pos = 4;
+
+    // client_version (2) + random (32).
+    pos += 2 + 32;
+
+    // session_id.
+    let session_len = *body.get(pos)? as usize;
+    pos += 1 + session_len;
+
+    // cipher_suites.
+    let cipher_len = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+    pos += 2 + cipher_len;
+
+    // compression_methods.
+    let compression_len = *body.get(pos)? as usize;
+    pos += 1 + compression_len;
+
+    // extensions length.
+    let ext_total = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+    pos += 2;
+    let ext_end = pos + ext_total;
+    if ext_end > body.len() {
+        return None;
+    }
+
+    // Walk the extensions looking for server_name (type 0x0000).
+    while pos + 4 <= ext_end {
+        let ext_type = u16::from_be_bytes([body[pos], body[pos + 1]]);
+        let ext_len = u16::from_be_bytes([body[pos + 2], body[pos + 3]]) as usize;
+        pos += 4;
+        let ext_data = body.get(pos..pos + ext_len)?;
+        pos += ext_len;
+
+        if ext_type == 0x0000 {
+            // server_name_list: list length (2), then entries of
+            // type (1) + length (2) + name.
+            if ext_data.len() < 2 {
+                return None;
+            }
+            let mut sp = 2;
+            while sp + 3 <= ext_data.len() {
+                let name_type = ext_data[sp];
+                let name_len =
+                    u16::from_be_bytes([ext_data[sp + 1], ext_data[sp + 2]]) as usize;
+                sp += 3;
+                let name = ext_data.get(sp..sp + name_len)?;
+                if name_type == 0 {
+                    return std::str::from_utf8(name).ok().map(|s| s.to_string());
+                }
+                sp += name_len;
+            }
+            return None;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_upgrade_request, parse_sni, ProxyMode};
+    use hyper::HeaderMap;
+
+    /// Build a minimal but well-formed TLS ClientHello record carrying a single
+    /// SNI `server_name`, used to exercise [`parse_sni`].
+    fn client_hello_with_sni(name: &str) -> Vec<u8> {
+        let name = name.as_bytes();
+
+        // server_name_list: one host_name entry (type 0 + len + name).
+        let mut sni_ext = Vec::new();
+        sni_ext.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+        sni_ext.push(0);
+        sni_ext.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        sni_ext.extend_from_slice(name);
+
+        let mut extensions = Vec::new();
+        extensions.extend_from_slice(&0x0000u16.to_be_bytes()); // server_name
+        extensions.extend_from_slice(&(sni_ext.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&sni_ext);
+
+        let mut hs_body = Vec::new();
+        hs_body.extend_from_slice(&[0x03, 0x03]); // client_version
+        hs_body.extend_from_slice(&[0u8; 32]); // random
+        hs_body.push(0); // session_id length
+        hs_body.extend_from_slice(&2u16.to_be_bytes()); // cipher_suites length
+        hs_body.extend_from_slice(&[0x00, 0x2f]); // one cipher suite
+        hs_body.push(1); // compression_methods length
+        hs_body.push(0); // null compression
+        hs_body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hs_body.extend_from_slice(&extensions);
+
+        let mut handshake = Vec::new();
+        handshake.push(0x01); // ClientHello
+        let hs_len = hs_body.len() as u32;
+        handshake.extend_from_slice(&[
+            ((hs_len >> 16) & 0xff) as u8,
+            ((hs_len >> 8) & 0xff) as u8,
+            (hs_len & 0xff) as u8,
+        ]);
+        handshake.extend_from_slice(&hs_body);
+
+        let mut record = Vec::new();
+        record.push(0x16); // handshake content type
+        record.extend_from_slice(&[0x03, 0x01]); // record version
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn parse_sni_extracts_server_name() {
+        let hello = client_hello_with_sni("example.com");
+        assert_eq!(parse_sni(&hello).as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn parse_sni_rejects_truncated_record() {
+        let hello = client_hello_with_sni("example.com");
+        // The record header still declares the full length, but the body is cut
+        // short; the parser must fall through to `None` rather than panic.
+        assert_eq!(parse_sni(&hello[..10]), None);
+    }
+
+    #[test]
+    fn parse_sni_rejects_non_handshake_byte() {
+        let mut hello = client_hello_with_sni("example.com");
+        hello[0] = 0x17; // application_data, not a handshake record
+        assert_eq!(parse_sni(&hello), None);
+    }
+
+    #[test]
+    fn detects_websocket_upgrade() {
+        let mut headers = HeaderMap::new();
+        headers.insert("connection", "Upgrade".parse().unwrap());
+        headers.insert("upgrade", "websocket".parse().unwrap());
+        assert!(is_upgrade_request(&headers));
+    }
+
+    #[test]
+    fn ignores_plain_requests() {
+        let mut headers = HeaderMap::new();
+        headers.insert("connection", "keep-alive".parse().unwrap());
+        assert!(!is_upgrade_request(&headers));
+
+        // Upgrade header without the matching Connection token is not an upgrade.
+        let mut headers = HeaderMap::new();
+        headers.insert("upgrade", "websocket".parse().unwrap());
+        assert!(!is_upgrade_request(&headers));
+    }
+
+    #[test]
+    fn proxy_protocol_v1_header() {
+        let src = "192.0.2.1:56324".parse().unwrap();
+        let dst = "198.51.100.5:443".parse().unwrap();
+        let header = ProxyMode::V1.header(src, dst).unwrap();
+        assert_eq!(header, b"PROXY TCP4 192.0.2.1 198.51.100.5 56324 443\r\n");
+    }
+
+    #[test]
+    fn proxy_protocol_v2_header() {
+        let src = "192.0.2.1:56324".parse().unwrap();
+        let dst = "198.51.100.5:443".parse().unwrap();
+        let header = ProxyMode::V2.header(src, dst).unwrap();
+        assert_eq!(
+            &header[..12],
+            &[0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A]
+        );
+        assert_eq!(header[12], 0x21); // version 2 + PROXY command
+        assert_eq!(header[13], 0x11); // AF_INET + STREAM
+        assert_eq!(header.len(), 28);
+    }
+
+    #[test]
+    fn proxy_protocol_off_and_mixed_family() {
+        let v4 = "192.0.2.1:1".parse().unwrap();
+        let v6 = "[2001:db8::1]:2".parse().unwrap();
+        assert!(ProxyMode::Off.header(v4, v4).is_none());
+        // Mismatched address families cannot be expressed; no header is written.
+        assert!(ProxyMode::V1.header(v4, v6).is_none());
+    }
+
+    /// End-to-end upgrade splice: stand up an echo upstream that accepts a
+    /// WebSocket handshake, drive a `Connection: upgrade` request through the
+    /// proxy, and confirm the upstream's `101` is relayed unmodified and bytes
+    /// round-trip across the spliced connection.
+    #[tokio::test]
+    async fn websocket_echo_round_trips_through_proxy() {
+        use crate::addon::filter::FilterRules;
+        use crate::addon::resolver::CacheResolver;
+        use http_body_util::{BodyExt, Empty};
+        use hyper::body::{Bytes, Incoming};
+        use hyper::server::conn::http1 as server_http1;
+        use hyper::service::service_fn;
+        use hyper::{Request, Response, StatusCode};
+        use hyper_util::rt::TokioIo;
+        use std::sync::Arc;
+
+        use super::handle_client;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        // Upstream echo server: accept an HTTP upgrade, reply 101, then echo
+        // every byte back over the upgraded connection.
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = upstream.accept().await.unwrap();
+            server_http1::Builder::new()
+                .serve_connection(
+                    TokioIo::new(stream),
+                    service_fn(|mut req: Request<Incoming>| async move {
+                        tokio::spawn(async move {
+                            if let Ok(upgraded) = hyper::upgrade::on(&mut req).await {
+                                let mut io = TokioIo::new(upgraded);
+                                let mut buf = [0u8; 64];
+                                loop {
+                                    match io.read(&mut buf).await {
+                                        Ok(0) | Err(_) => break,
+                                        Ok(n) => {
+                                            if io.write_all(&buf[..n]).await.is_err() {
+                                                break;
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        });
+                        let res = Response::builder()
+                            .status(StatusCode::SWITCHING_PROTOCOLS)
+                            .header("connection", "upgrade")
+                            .header("upgrade", "websocket")
+                            .body(Empty::<Bytes>::new().map_err(|never| match never {}).boxed())
+                            .unwrap();
+                        Ok::<_, hyper::Error>(res)
+                    }),
+                )
+                .with_upgrades()
+                .await
+                .unwrap();
+        });
+
+        // Proxy server under test.
+        let proxy_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy_listener.local_addr().unwrap();
+        let rules = Arc::new(FilterRules::new_blacklist(Vec::<String>::new()));
+        let resolver = Arc::new(CacheResolver::new().unwrap());
+        tokio::spawn(async move {
+            let (stream, peer) = proxy_listener.accept().await.unwrap();
+            handle_client(stream, peer, rules, resolver, None, false, ProxyMode::Off)
+                .await
+                .unwrap();
+        });
+
+        // Client: send an absolute-form upgrade request to the proxy and read
+        // the response head up to the blank line.
+        let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let request = format!(
+            "GET http://{host}/ HTTP/1.1\r\nHost: {host}\r\nConnection: upgrade\r\nUpgrade: websocket\r\n\r\n",
+            host = upstream_addr
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut head = Vec::new();
+        let mut tmp = [0u8; 256];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut tmp).await.unwrap();
+            assert!(n > 0, "proxy closed connection before sending a response");
+            head.extend_from_slice(&tmp[..n]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        assert!(head.starts_with("HTTP/1.1 101"), "unexpected status: {head}");
+        assert!(
+            head.to_ascii_lowercase().contains("upgrade: websocket"),
+            "upgrade header not relayed: {head}"
+        );
+
+        // The connection is now a raw byte pipe spliced through to the echo
+        // upstream; confirm a payload round-trips.
+        stream.write_all(b"hello websocket").await.unwrap();
+        let mut echoed = [0u8; 15];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello websocket");
+    }
+}